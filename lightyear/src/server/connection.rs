@@ -1,6 +1,8 @@
 //! Specify how a Server sends/receives messages with a Client
 use bevy::utils::{Duration, EntityHashMap, Entry, HashMap};
+use bytes::Bytes;
 use std::rc::Rc;
+use std::time::Instant;
 
 use crate::_reexport::{
     EntityUpdatesChannel, InputMessageKind, MessageBehaviour, MessageKind, MessageProtocol,
@@ -34,10 +36,34 @@ use crate::shared::replication::receive::ReplicationReceiver;
 use crate::shared::replication::send::ReplicationSender;
 use crate::shared::replication::ReplicationMessage;
 use crate::shared::replication::ReplicationMessageData;
+#[cfg(feature = "discovery")]
+use crate::server::discovery::ServerAdvertiser;
+use crate::server::input_pacing::InputPacingController;
+use crate::server::qos::{BandwidthBudget, PriorityScheduler};
+use crate::server::rpc::RpcState;
+use crate::server::streaming::{StreamReceiver, StreamSender, FRAGMENT_SIZE};
 use crate::shared::tick_manager::Tick;
 use crate::shared::tick_manager::TickManager;
 use crate::shared::time_manager::TimeManager;
 
+/// Maximum number of stream-fragment bytes a single connection may flush per `send_packets`
+/// call, so a large streamed transfer can't starve the normal channels.
+const STREAM_BYTE_BUDGET_PER_TICK: usize = 8 * FRAGMENT_SIZE;
+
+/// Default per-connection bandwidth cap, used to derive the per-tick byte budget for
+/// `send_packets` until the user configures one explicitly.
+const DEFAULT_BANDWIDTH_CAP_BYTES_PER_SECOND: usize = 256 * 1024;
+/// Assumed `send_packets` call rate, used together with the bandwidth cap above to derive a
+/// per-tick byte budget.
+const DEFAULT_SEND_HZ: f64 = 64.0;
+
+/// Target number of ticks of buffered input we'd like to be holding ahead of the current
+/// tick, used as the setpoint for [`InputPacingController`]. Matches the `0.0`/`1.0`
+/// occupancy stand-in fed by `pop_inputs` (a healthy connection observes `1.0` every tick),
+/// so the controller's correction actually settles to zero instead of chasing an
+/// unreachable setpoint forever.
+const DEFAULT_INPUT_TARGET_DEPTH_TICKS: f32 = 1.0;
+
 pub struct ConnectionManager<P: Protocol> {
     pub(crate) connections: HashMap<ClientId, Connection<P>>,
     channel_registry: ChannelRegistry,
@@ -51,6 +77,11 @@ pub struct ConnectionManager<P: Protocol> {
     // list of clients that connected since the last time we sent replication messages
     // (we want to keep track of them because we need to replicate the entire world state to them)
     pub(crate) new_clients: Vec<ClientId>,
+
+    /// Advertises this server on the LAN via mDNS so clients can discover it without knowing
+    /// the address up front. `None` unless the `discovery` feature is enabled.
+    #[cfg(feature = "discovery")]
+    pub advertiser: Option<ServerAdvertiser>,
 }
 
 impl<P: Protocol> ConnectionManager<P> {
@@ -61,6 +92,37 @@ impl<P: Protocol> ConnectionManager<P> {
             events: ServerEvents::new(),
             replicate_component_cache: EntityHashMap::default(),
             new_clients: vec![],
+            #[cfg(feature = "discovery")]
+            advertiser: None,
+        }
+    }
+
+    /// Starts advertising this server on the LAN via mDNS under `server_name`, with the given
+    /// protocol version and port. Replaces any advertiser already set, so it's safe to call
+    /// again (e.g. after a port change) without calling [`disable_discovery`](Self::disable_discovery) first.
+    #[cfg(feature = "discovery")]
+    pub fn enable_discovery(&mut self, server_name: impl Into<String>, protocol_version: u32, port: u16) {
+        self.disable_discovery();
+        let mut advertiser = ServerAdvertiser::new(server_name, protocol_version, port);
+        advertiser.set_player_count(self.connections.len());
+        advertiser.set_enabled(true);
+        self.advertiser = Some(advertiser);
+    }
+
+    /// Stops advertising this server on the LAN, withdrawing the mDNS record if one was active.
+    #[cfg(feature = "discovery")]
+    pub fn disable_discovery(&mut self) {
+        if let Some(mut advertiser) = self.advertiser.take() {
+            advertiser.set_enabled(false);
+        }
+    }
+
+    /// Refreshes the advertised player count (if LAN discovery is enabled) after a client
+    /// joins or leaves.
+    #[cfg(feature = "discovery")]
+    fn refresh_advertised_player_count(&mut self) {
+        if let Some(advertiser) = &mut self.advertiser {
+            advertiser.set_player_count(self.connections.len());
         }
     }
 
@@ -76,6 +138,23 @@ impl<P: Protocol> ConnectionManager<P> {
             .context("client id not found")
     }
 
+    /// Sends `req` to `client_id` and returns a future that resolves with the matching
+    /// response. A thin convenience over [`Connection::request`] for callers that only have
+    /// the `ClientId` at hand, not the `Connection` itself.
+    pub fn request<Req, Resp>(
+        &mut self,
+        client_id: ClientId,
+        req: &Req,
+        channel: ChannelKind,
+        timeout: Duration,
+    ) -> Result<impl std::future::Future<Output = Result<Resp>>>
+    where
+        Req: Serialize,
+        Resp: for<'de> Deserialize<'de>,
+    {
+        self.connection_mut(client_id)?.request(req, channel, timeout)
+    }
+
     pub(crate) fn update(&mut self, time_manager: &TimeManager, tick_manager: &TickManager) {
         self.connections.values_mut().for_each(|connection| {
             connection.update(time_manager, tick_manager);
@@ -92,6 +171,8 @@ impl<P: Protocol> ConnectionManager<P> {
             connection.events.push_connection();
             self.new_clients.push(client_id);
             e.insert(connection);
+            #[cfg(feature = "discovery")]
+            self.refresh_advertised_player_count();
         } else {
             info!("Client {} was already in the connections list", client_id);
         }
@@ -103,7 +184,12 @@ impl<P: Protocol> ConnectionManager<P> {
 
         info!("Client {} disconnected", client_id);
         self.events.push_disconnects(client_id);
+        if let Some(connection) = self.connections.get_mut(&client_id) {
+            connection.cancel_streams();
+        }
         self.connections.remove(&client_id);
+        #[cfg(feature = "discovery")]
+        self.refresh_advertised_player_count();
     }
 
     /// Get the inputs for all clients for the given tick
@@ -135,9 +221,16 @@ impl<P: Protocol> ConnectionManager<P> {
                     "Missed client input!"
                     )
                 }
-                // TODO: We should also let the user know that it needs to send inputs a bit earlier so that
-                //  we have more of a buffer. Send a SyncMessage to tell the user to speed up?
-                //  See Overwatch GDC video
+                // feed this tick's occupancy into the pacing controller so it can ask the
+                // client to shift its input send-ahead if we're running dry (see Overwatch
+                // GDC video on input buffering)
+                // TODO: `InputBuffer` (outside this checkout) should expose the actual number
+                //  of ticks of input still buffered ahead of `tick`; until then we use 0/1 as
+                //  a coarse stand-in for "ran dry this tick" / "had input this tick".
+                let observed_depth_ticks = if fallback { 0.0 } else { 1.0 };
+                connection
+                    .input_pacing
+                    .record_sample(observed_depth_ticks, fallback);
                 (input, *client_id)
             })
     }
@@ -148,15 +241,36 @@ impl<P: Protocol> ConnectionManager<P> {
         channel: ChannelKind,
         target: NetworkTarget,
     ) -> Result<()> {
-        // Rc is fine because the copies are all created on the same thread
-        // let message = Rc::new(message);
-        self.connections
+        let mut recipients = self
+            .connections
             .iter_mut()
-            .filter(|(id, _)| target.should_send_to(id))
-            // TODO: here we should avoid the clone, it's the same message.. just use Rc?
-            //  need to update the ServerMessage enum to use Rc<P::Message>!
-            //  or serialize first, so we can use Bytes? where would the buffer be?
-            .try_for_each(|(_, c)| c.buffer_message(message.clone(), channel))
+            .filter(|(id, _)| target.should_send_to(id));
+
+        // for a single recipient there's nothing to share, so hand the message straight to
+        // that connection (which serializes it once itself)
+        let Some((_, first)) = recipients.next() else {
+            return Ok(());
+        };
+        let Some((_, second)) = recipients.next() else {
+            return first.buffer_message(message, channel);
+        };
+
+        // broadcasting to more than one client: serialize once into a ref-counted buffer and
+        // give every connection a cheap clone of it instead of re-serializing per connection.
+        // Go through `message_manager.serialize` (the same encoder `Connection::buffer_message`
+        // uses for the single-recipient path above) so a broadcast and a unicast of the
+        // identical message put identical bytes on the wire.
+        let message = ServerMessage::<P>::Message(message);
+        let channel_name = self
+            .channel_registry
+            .name(&channel)
+            .unwrap_or("unknown")
+            .to_string();
+        message.emit_send_logs(&channel_name);
+        let bytes = first.message_manager.serialize(&message)?;
+        first.buffer_message_bytes(bytes.clone(), channel)?;
+        second.buffer_message_bytes(bytes.clone(), channel)?;
+        recipients.try_for_each(|(_, c)| c.buffer_message_bytes(bytes.clone(), channel))
     }
 
     pub(crate) fn buffer_replication_messages(&mut self, tick: Tick) -> Result<()> {
@@ -204,6 +318,28 @@ pub struct Connection<P: Protocol> {
 
     // messages that we have received that need to be rebroadcasted to other clients
     pub(crate) messages_to_rebroadcast: Vec<(P::Message, NetworkTarget, ChannelKind)>,
+
+    /// Splits large outgoing payloads (level data, asset bundles, initial snapshots, ...)
+    /// into fragments and doles them out across `send_packets` calls.
+    pub(crate) stream_sender: StreamSender,
+    /// Reassembles fragments received from this client back into complete payloads.
+    pub(crate) stream_receiver: StreamReceiver,
+    /// Fully reassembled stream payloads from this client, ready to be drained by the caller
+    /// (e.g. a system reading `Connection::take_completed_streams`).
+    pub(crate) completed_streams: Vec<(ChannelKind, Vec<u8>)>,
+
+    /// Tracks outstanding `request`/`respond` RPCs and registered request handlers for this
+    /// connection.
+    pub(crate) rpc: RpcState,
+
+    /// Per-channel send priority for this connection's `send_packets` calls.
+    pub(crate) priority_scheduler: PriorityScheduler,
+    /// Per-tick byte budget derived from the configured bandwidth cap.
+    pub(crate) bandwidth_budget: BandwidthBudget,
+
+    /// Closed-loop controller nudging this client to send inputs earlier/later so its input
+    /// buffer stays just deep enough to absorb jitter.
+    pub(crate) input_pacing: InputPacingController,
 }
 
 impl<P: Protocol> Connection<P> {
@@ -228,13 +364,106 @@ impl<P: Protocol> Connection<P> {
             last_input: None,
             events: ConnectionEvents::default(),
             messages_to_rebroadcast: vec![],
+            stream_sender: StreamSender::default(),
+            stream_receiver: StreamReceiver::default(),
+            completed_streams: vec![],
+            rpc: RpcState::default(),
+            priority_scheduler: {
+                let mut scheduler = PriorityScheduler::default();
+                // ping/pong must never be delayed behind bulk replication, else our RTT/time
+                // sync measurements get skewed by queueing delay
+                scheduler.mark_always_send(ChannelKind::of::<PingChannel>());
+                scheduler
+            },
+            bandwidth_budget: BandwidthBudget::from_bytes_per_second(
+                DEFAULT_BANDWIDTH_CAP_BYTES_PER_SECOND,
+                DEFAULT_SEND_HZ,
+            ),
+            input_pacing: InputPacingController::new(DEFAULT_INPUT_TARGET_DEPTH_TICKS),
         }
     }
 
+    /// Sets the per-channel priority weight used by the [`PriorityScheduler`] when deciding
+    /// which channels to drain first under a tight bandwidth budget.
+    pub fn set_channel_priority(&mut self, channel: ChannelKind, weight: u32) {
+        self.priority_scheduler.set_weight(channel, weight);
+    }
+
+    /// Sends `req` to this client and returns a future that resolves with the matching
+    /// response, or an error if the connection is dropped or `timeout` elapses first.
+    ///
+    /// The response travels back on `channel` (pick a reliable channel unless you have a
+    /// reason not to); pending requests are cleaned up automatically on timeout or when the
+    /// connection is removed.
+    pub fn request<Req, Resp>(
+        &mut self,
+        req: &Req,
+        channel: ChannelKind,
+        timeout: Duration,
+    ) -> Result<impl std::future::Future<Output = Result<Resp>>>
+    where
+        Req: Serialize,
+        Resp: for<'de> Deserialize<'de>,
+    {
+        let (request_id, receiver) = self.rpc.start_request(timeout);
+        let request_bytes = bincode::serialize(req)?;
+        let request_kind = std::any::type_name::<Req>().to_string();
+        trace!(request_id, ?channel, request_kind, "sending RPC request");
+        self.message_manager.buffer_send(
+            ServerMessage::<P>::Request(request_id, request_kind, request_bytes),
+            channel,
+        )?;
+        Ok(async move {
+            receiver
+                .await
+                .map_err(|_| anyhow::anyhow!("connection was removed before request completed"))?
+                .and_then(|bytes| bincode::deserialize(&bytes).map_err(Into::into))
+        })
+    }
+
+    /// Registers the handler that answers incoming requests of type `Req` from this client.
+    pub fn register_request_handler<Req: 'static, Resp: Serialize>(
+        &mut self,
+        handler: impl Fn(Req) -> Resp + Send + Sync + 'static,
+    ) where
+        Req: for<'de> Deserialize<'de>,
+    {
+        self.rpc.register_handler::<Req>(move |bytes| {
+            let req: Req = bincode::deserialize(bytes)?;
+            Ok(bincode::serialize(&handler(req))?)
+        });
+    }
+
+    /// Queues `payload` to be sent as a fragmented stream on `channel` instead of going
+    /// through the normal single-packet message path.
+    ///
+    /// Use this for payloads that can exceed a single packet's MTU (level/map data, asset
+    /// bundles, initial world snapshots); ordinary messages should keep using
+    /// [`Connection::buffer_message`].
+    pub fn buffer_stream_message(&mut self, payload: Vec<u8>, channel: ChannelKind) -> Result<()> {
+        self.stream_sender.start_stream(payload, channel)?;
+        Ok(())
+    }
+
+    /// Drains and returns the stream payloads that finished reassembling since the last call.
+    pub fn take_completed_streams(&mut self) -> Vec<(ChannelKind, Vec<u8>)> {
+        std::mem::take(&mut self.completed_streams)
+    }
+
+    /// Cancels any in-flight fragmented transfers to/from this client, e.g. right before the
+    /// connection is removed.
+    pub(crate) fn cancel_streams(&mut self) {
+        self.stream_sender.clear();
+        self.stream_receiver.clear();
+    }
+
     pub(crate) fn update(&mut self, time_manager: &TimeManager, tick_manager: &TickManager) {
         self.message_manager
             .update(time_manager, &self.ping_manager, tick_manager);
         self.ping_manager.update(time_manager);
+        self.rpc.expire_timed_out(Instant::now());
+        self.input_pacing
+            .update(time_manager.delta(), !time_manager.is_synced());
     }
 
     pub(crate) fn buffer_message(
@@ -252,8 +481,24 @@ impl<P: Protocol> Connection<P> {
             .to_string();
         let message = ServerMessage::<P>::Message(message);
         message.emit_send_logs(&channel_name);
-        self.message_manager.buffer_send(message, channel)?;
-        Ok(())
+        // route through the same serialize + buffer_send_bytes path the broadcast case uses,
+        // so there is exactly one place that encodes a `ServerMessage` onto the wire
+        let bytes = self.message_manager.serialize(&message)?;
+        self.buffer_message_bytes(bytes, channel)
+    }
+
+    /// Buffers an already-serialized `ServerMessage::<P>::Message` for sending, without
+    /// re-serializing it. Used by [`ConnectionManager::buffer_message`] when broadcasting the
+    /// same message to several connections, so the serialization happens exactly once.
+    pub(crate) fn buffer_message_bytes(&mut self, bytes: Bytes, channel: ChannelKind) -> Result<()> {
+        let channel_name = self
+            .message_manager
+            .channel_registry
+            .name(&channel)
+            .unwrap_or("unknown")
+            .to_string();
+        trace!(?channel_name, num_bytes = bytes.len(), "buffering serialized message");
+        self.message_manager.buffer_send_bytes(bytes, channel)
     }
 
     pub(crate) fn buffer_replication_messages(&mut self, tick: Tick) -> Result<()> {
@@ -323,8 +568,45 @@ impl<P: Protocol> Connection<P> {
                     Ok::<(), anyhow::Error>(())
                 })?;
         }
-        self.message_manager
-            .send_packets(tick_manager.current_tick())
+        if let Some(delta_ticks) = self.input_pacing.take_pending_correction() {
+            trace!(delta_ticks, "sending input pacing correction to client");
+            let message = ServerMessage::<P>::Sync(SyncMessage::InputPacing(delta_ticks));
+            let channel = ChannelKind::of::<PingChannel>();
+            self.message_manager.buffer_send(message, channel)?;
+        }
+
+        // bump every channel's priority accumulator and work out this cycle's send order
+        // before handing the byte budget off to the channels themselves
+        self.priority_scheduler
+            .begin_cycle(self.message_manager.channels.keys().copied());
+        let channel_order = self.priority_scheduler.schedule_order();
+
+        // flush as many queued stream fragments as fit in this tick's byte budget. Clamped to
+        // at least one `FRAGMENT_SIZE`: below that, the front fragment's length always exceeds
+        // the budget and `drain` would break immediately every tick, stalling the stream
+        // forever instead of trading a little burstiness for forward progress.
+        let stream_budget = STREAM_BYTE_BUDGET_PER_TICK
+            .min(self.bandwidth_budget.cap_per_tick)
+            .max(FRAGMENT_SIZE);
+        for (channel, fragment) in self.stream_sender.drain(stream_budget) {
+            trace!(
+                stream_id = fragment.stream_id,
+                fragment_index = fragment.fragment_index,
+                "Sending stream fragment"
+            );
+            self.message_manager
+                .buffer_send(ServerMessage::<P>::Stream(fragment), channel)?;
+        }
+
+        let (payloads, bytes_sent_per_channel) = self.message_manager.send_packets_prioritized(
+            tick_manager.current_tick(),
+            &channel_order,
+            self.bandwidth_budget.cap_per_tick,
+        )?;
+        for (channel, bytes_sent) in bytes_sent_per_channel {
+            self.priority_scheduler.record_sent(channel, bytes_sent);
+        }
+        Ok(payloads)
     }
 
     pub fn receive(
@@ -380,6 +662,43 @@ impl<P: Protocol> Connection<P> {
                             // buffer the replication message
                             self.replication_receiver.recv_message(replication, tick);
                         }
+                        ClientMessage::Stream(fragment) => {
+                            match self.stream_receiver.receive_fragment(fragment) {
+                                Ok(Some(payload)) => {
+                                    trace!(num_bytes = payload.len(), "stream reassembled");
+                                    self.completed_streams.push((channel_kind, payload));
+                                }
+                                Ok(None) => {}
+                                Err(e) => debug!(?e, "dropping invalid stream fragment"),
+                            }
+                        }
+                        ClientMessage::Request(request_id, request_kind, payload) => {
+                            match self.rpc.dispatch_request(&request_kind, &payload) {
+                                Ok(response_payload) => {
+                                    if let Err(e) = self.message_manager.buffer_send(
+                                        ServerMessage::<P>::Response(request_id, response_payload),
+                                        channel_kind,
+                                    ) {
+                                        debug!(?e, request_id, "failed to buffer RPC response");
+                                    }
+                                }
+                                Err(e) => {
+                                    debug!(?e, request_id, request_kind, "no handler for incoming RPC request; replying with an error so the caller doesn't wait out the full timeout");
+                                    if let Err(send_err) = self.message_manager.buffer_send(
+                                        ServerMessage::<P>::RequestError(request_id, e.to_string()),
+                                        channel_kind,
+                                    ) {
+                                        debug!(?send_err, request_id, "failed to buffer RPC error response");
+                                    }
+                                }
+                            }
+                        }
+                        ClientMessage::Response(request_id, payload) => {
+                            self.rpc.complete_request(request_id, payload);
+                        }
+                        ClientMessage::RequestError(request_id, message) => {
+                            self.rpc.fail_request(request_id, message);
+                        }
                         ClientMessage::Sync(ref sync) => {
                             match sync {
                                 SyncMessage::Ping(ping) => {
@@ -0,0 +1,373 @@
+//! Optional mDNS-based LAN discovery, so clients can find a server without knowing its
+//! address up front.
+//!
+//! [`ServerAdvertiser`] publishes the running server as an mDNS service record (name, current
+//! player count, protocol version, port) and keeps the TXT record up to date as players
+//! join/leave. [`ClientBrowser`] browses for those records and hands back the list of servers
+//! currently visible on the LAN, expiring entries that haven't refreshed recently.
+//!
+//! Advertising and browsing are independently toggleable at runtime, so a dedicated/public
+//! server can turn advertising off and headless tooling can skip browsing entirely. This
+//! module is gated behind the `discovery` feature and pulled in by nothing in the core
+//! netcode path, so builds that don't want the mDNS dependency don't pay for it.
+//!
+//! The actual daemon calls go through [`MdnsBackend`], a small seam that keeps this module's
+//! enable/disable and TXT-record bookkeeping testable without a real mDNS daemon, and that
+//! decouples it from the `mdns-sd` crate itself (a new optional dependency this checkout has
+//! no manifest to add). [`ServerAdvertiser::new`]/[`ClientBrowser::default`] use
+//! [`NullMdnsBackend`], which only logs; swap in a real backend (e.g. a `MdnsSdBackend`
+//! wrapping `mdns_sd::ServiceDaemon`) via [`ServerAdvertiser::with_backend`]/
+//! [`ClientBrowser::with_backend`] once that dependency is available, without reshaping any of
+//! the public API above this seam.
+#![cfg(feature = "discovery")]
+
+use bevy::utils::{Duration, HashMap};
+use std::net::IpAddr;
+use std::time::Instant;
+
+use tracing::{debug, trace};
+
+/// The mDNS service type servers are advertised under and clients browse for.
+const SERVICE_TYPE: &str = "_lightyear._udp.local.";
+
+/// Performs the actual mDNS daemon operations behind [`ServerAdvertiser`]/[`ClientBrowser`].
+///
+/// Swapping this out is how a real `mdns-sd`-backed daemon gets wired in: implement it against
+/// `mdns_sd::ServiceDaemon` (`register`/`unregister` for advertising, `browse`/`stop_browse` for
+/// browsing) and hand it to [`ServerAdvertiser::with_backend`]/[`ClientBrowser::with_backend`].
+/// TXT values are passed as plain key/value pairs rather than a pre-built record so a backend
+/// can decide its own encoding.
+pub trait MdnsBackend: Send + Sync {
+    /// Starts publishing `service_name` under [`SERVICE_TYPE`] on `port`, with `txt` as its
+    /// TXT record.
+    fn advertise(&mut self, service_name: &str, port: u16, txt: &HashMap<String, String>);
+    /// Withdraws a previously-started advertisement for `service_name`.
+    fn withdraw(&mut self, service_name: &str);
+    /// Pushes an updated TXT record for an advertisement already in progress.
+    fn update_txt(&mut self, service_name: &str, txt: &HashMap<String, String>);
+    /// Starts browsing for services under [`SERVICE_TYPE`].
+    fn start_browse(&mut self);
+    /// Stops a browse started by [`MdnsBackend::start_browse`].
+    fn stop_browse(&mut self);
+}
+
+/// The backend used until a real `mdns-sd` integration is wired in: every operation is a no-op
+/// besides a trace log, so advertising/browsing toggle cleanly but don't reach the network.
+#[derive(Default)]
+pub struct NullMdnsBackend;
+
+impl MdnsBackend for NullMdnsBackend {
+    fn advertise(&mut self, service_name: &str, port: u16, txt: &HashMap<String, String>) {
+        trace!(service_name, port, ?txt, "NullMdnsBackend: would advertise");
+    }
+
+    fn withdraw(&mut self, service_name: &str) {
+        trace!(service_name, "NullMdnsBackend: would withdraw advertisement");
+    }
+
+    fn update_txt(&mut self, service_name: &str, txt: &HashMap<String, String>) {
+        trace!(service_name, ?txt, "NullMdnsBackend: would update TXT record");
+    }
+
+    fn start_browse(&mut self) {
+        trace!(SERVICE_TYPE, "NullMdnsBackend: would start browsing");
+    }
+
+    fn stop_browse(&mut self) {
+        trace!(SERVICE_TYPE, "NullMdnsBackend: would stop browsing");
+    }
+}
+
+/// An entry seen while browsing, expired if not refreshed within [`ClientBrowser::ttl`].
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    pub name: String,
+    pub address: IpAddr,
+    pub port: u16,
+    pub player_count: usize,
+    pub protocol_version: u32,
+    last_seen: Instant,
+}
+
+impl DiscoveredServer {
+    /// Builds a freshly-seen entry, e.g. from a resolved mDNS service record, stamped with the
+    /// current time so it isn't immediately considered stale.
+    pub fn new(
+        name: impl Into<String>,
+        address: IpAddr,
+        port: u16,
+        player_count: usize,
+        protocol_version: u32,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            address,
+            port,
+            player_count,
+            protocol_version,
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+/// Advertises this server on the local network via mDNS.
+///
+/// Call [`ServerAdvertiser::set_player_count`] whenever a client joins or leaves so the
+/// advertised TXT record stays accurate for browsing clients.
+pub struct ServerAdvertiser {
+    server_name: String,
+    protocol_version: u32,
+    port: u16,
+    player_count: usize,
+    enabled: bool,
+    backend: Box<dyn MdnsBackend>,
+}
+
+impl ServerAdvertiser {
+    pub fn new(server_name: impl Into<String>, protocol_version: u32, port: u16) -> Self {
+        Self::with_backend(server_name, protocol_version, port, Box::new(NullMdnsBackend))
+    }
+
+    /// Like [`ServerAdvertiser::new`], but publishing through a specific [`MdnsBackend`] (e.g. a
+    /// real `mdns-sd`-backed one) instead of the default no-op [`NullMdnsBackend`].
+    pub fn with_backend(
+        server_name: impl Into<String>,
+        protocol_version: u32,
+        port: u16,
+        backend: Box<dyn MdnsBackend>,
+    ) -> Self {
+        Self {
+            server_name: server_name.into(),
+            protocol_version,
+            port,
+            player_count: 0,
+            enabled: false,
+            backend,
+        }
+    }
+
+    fn txt_record(&self) -> HashMap<String, String> {
+        let mut txt = HashMap::default();
+        txt.insert("player_count".to_string(), self.player_count.to_string());
+        txt.insert("protocol_version".to_string(), self.protocol_version.to_string());
+        txt
+    }
+
+    /// Starts (or stops) publishing the service record. Safe to call repeatedly; toggling this
+    /// off immediately withdraws the advertisement so browsing clients stop seeing us.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled == self.enabled {
+            return;
+        }
+        debug!(enabled, server_name = %self.server_name, "toggling mDNS server advertisement");
+        self.enabled = enabled;
+        if enabled {
+            let txt = self.txt_record();
+            self.backend.advertise(&self.server_name, self.port, &txt);
+        } else {
+            self.backend.withdraw(&self.server_name);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Updates the advertised player count, e.g. from `connections.len()` after an `add`/
+    /// `remove`. Refreshes the TXT record if we're currently advertising.
+    pub fn set_player_count(&mut self, player_count: usize) {
+        self.player_count = player_count;
+        if self.enabled {
+            trace!(player_count, "refreshing advertised mDNS TXT record");
+            let txt = self.txt_record();
+            self.backend.update_txt(&self.server_name, &txt);
+        }
+    }
+}
+
+/// Browses the local network for servers advertised via [`ServerAdvertiser`].
+pub struct ClientBrowser {
+    discovered: HashMap<String, DiscoveredServer>,
+    enabled: bool,
+    ttl: Duration,
+    backend: Box<dyn MdnsBackend>,
+}
+
+impl Default for ClientBrowser {
+    fn default() -> Self {
+        Self::with_backend(Box::new(NullMdnsBackend))
+    }
+}
+
+impl ClientBrowser {
+    /// Like [`ClientBrowser::default`], but browsing through a specific [`MdnsBackend`] (e.g. a
+    /// real `mdns-sd`-backed one) instead of the default no-op [`NullMdnsBackend`].
+    pub fn with_backend(backend: Box<dyn MdnsBackend>) -> Self {
+        Self {
+            discovered: HashMap::default(),
+            enabled: false,
+            // a server re-advertises well within this window under normal operation; anything
+            // older is assumed to have gone away without a clean withdrawal
+            ttl: Duration::from_secs(30),
+            backend,
+        }
+    }
+
+    /// Starts (or stops) browsing for servers. Safe to call repeatedly.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled == self.enabled {
+            return;
+        }
+        self.enabled = enabled;
+        if enabled {
+            self.backend.start_browse();
+        } else {
+            self.backend.stop_browse();
+            self.discovered.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Records (or refreshes) a discovered server. This is the entry point a real backend's
+    /// resolve-event loop calls as resolve events come in.
+    pub fn on_resolved(&mut self, server: DiscoveredServer) {
+        self.discovered.insert(server.name.clone(), server);
+    }
+
+    /// Drops any discovered servers that haven't refreshed within the TTL.
+    pub fn expire_stale(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.discovered.retain(|name, server| {
+            let fresh = now.duration_since(server.last_seen) < ttl;
+            if !fresh {
+                debug!(name, "mDNS discovered server entry expired");
+            }
+            fresh
+        });
+    }
+
+    /// Returns the currently known servers. Entries past the TTL are not filtered out here;
+    /// they are only dropped by [`ClientBrowser::expire_stale`].
+    pub fn discovered_servers(&self) -> impl Iterator<Item = &DiscoveredServer> {
+        self.discovered.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    enum BackendCall {
+        Advertise(String, u16),
+        Withdraw(String),
+        UpdateTxt(String, String),
+        StartBrowse,
+        StopBrowse,
+    }
+
+    #[derive(Default, Clone)]
+    struct RecordingBackend {
+        calls: Arc<Mutex<Vec<BackendCall>>>,
+    }
+
+    impl MdnsBackend for RecordingBackend {
+        fn advertise(&mut self, service_name: &str, port: u16, _txt: &HashMap<String, String>) {
+            self.calls.lock().unwrap().push(BackendCall::Advertise(service_name.to_string(), port));
+        }
+        fn withdraw(&mut self, service_name: &str) {
+            self.calls.lock().unwrap().push(BackendCall::Withdraw(service_name.to_string()));
+        }
+        fn update_txt(&mut self, service_name: &str, txt: &HashMap<String, String>) {
+            self.calls.lock().unwrap().push(BackendCall::UpdateTxt(
+                service_name.to_string(),
+                txt.get("player_count").cloned().unwrap_or_default(),
+            ));
+        }
+        fn start_browse(&mut self) {
+            self.calls.lock().unwrap().push(BackendCall::StartBrowse);
+        }
+        fn stop_browse(&mut self) {
+            self.calls.lock().unwrap().push(BackendCall::StopBrowse);
+        }
+    }
+
+    fn server(name: &str, last_seen: Instant) -> DiscoveredServer {
+        DiscoveredServer {
+            name: name.to_string(),
+            address: IpAddr::from([127, 0, 0, 1]),
+            port: 1234,
+            player_count: 0,
+            protocol_version: 1,
+            last_seen,
+        }
+    }
+
+    #[test]
+    fn enabling_advertises_and_disabling_withdraws() {
+        let backend = RecordingBackend::default();
+        let mut advertiser = ServerAdvertiser::with_backend("my-server", 1, 7777, Box::new(backend.clone()));
+        advertiser.set_enabled(true);
+        advertiser.set_enabled(false);
+        let calls = backend.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            vec![
+                BackendCall::Advertise("my-server".to_string(), 7777),
+                BackendCall::Withdraw("my-server".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_player_count_only_refreshes_the_backend_while_enabled() {
+        let backend = RecordingBackend::default();
+        let mut advertiser = ServerAdvertiser::with_backend("my-server", 1, 7777, Box::new(backend.clone()));
+        advertiser.set_player_count(3);
+        assert!(backend.calls.lock().unwrap().is_empty());
+
+        advertiser.set_enabled(true);
+        advertiser.set_player_count(4);
+        let calls = backend.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            vec![
+                BackendCall::Advertise("my-server".to_string(), 7777),
+                BackendCall::UpdateTxt("my-server".to_string(), "4".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn browser_enable_disable_drives_the_backend_and_clears_on_disable() {
+        let backend = RecordingBackend::default();
+        let mut browser = ClientBrowser::with_backend(Box::new(backend.clone()));
+        browser.set_enabled(true);
+        browser.on_resolved(server("a", Instant::now()));
+        assert_eq!(browser.discovered_servers().count(), 1);
+
+        browser.set_enabled(false);
+        assert_eq!(browser.discovered_servers().count(), 0);
+        assert_eq!(
+            backend.calls.lock().unwrap().clone(),
+            vec![BackendCall::StartBrowse, BackendCall::StopBrowse]
+        );
+    }
+
+    #[test]
+    fn expire_stale_drops_only_entries_past_the_ttl() {
+        let mut browser = ClientBrowser::default();
+        let now = Instant::now();
+        browser.on_resolved(server("fresh", now));
+        browser.on_resolved(server("stale", now - Duration::from_secs(60)));
+
+        browser.expire_stale(now);
+
+        let remaining: Vec<_> = browser.discovered_servers().map(|s| s.name.clone()).collect();
+        assert_eq!(remaining, vec!["fresh".to_string()]);
+    }
+}
@@ -0,0 +1,90 @@
+//! Closed-loop pacing feedback telling a client to send inputs earlier (or later) so its
+//! buffer neither runs dry nor carries more slack than it needs.
+//!
+//! `ConnectionManager::pop_inputs` already falls back to the last known input when a tick's
+//! input is missing; [`InputPacingController`] turns that same per-tick occupancy signal into
+//! a smoothed correction the client can act on, instead of just logging the miss (see the
+//! Overwatch GDC talk on input buffering this mirrors).
+//!
+//! Corrections are computed here and queued on [`InputPacingController::pending_correction`];
+//! `Connection::send_packets` drains them and ships each one as a
+//! `SyncMessage::InputPacing(i8)`.
+//!
+//! KNOWN LIMITATION: with `pop_inputs`'s coarse `0.0`/`1.0` occupancy stand-in (see the TODO
+//! there) and a `target_depth_ticks` of `1.0`, `ema_depth_ticks` can never exceed the target,
+//! so `raw_correction = target - ema` never goes negative. In practice this makes the
+//! controller one-directional: it can ask a client to send *earlier* (buffer is running dry)
+//! but can never ask it to send *later* (buffer has more slack than it needs), so a client that
+//! over-buffers keeps its extra latency forever instead of reclaiming it. Fixing this properly
+//! needs `InputBuffer` (outside this checkout) to expose real buffered-ticks-ahead instead of
+//! a boolean fallback flag.
+use bevy::utils::Duration;
+
+/// Smoothing factor for the occupancy/fallback EMAs. Lower is smoother but slower to react.
+const EMA_ALPHA: f32 = 0.1;
+
+/// Largest adjustment (in ticks) sent to the client in a single update, to avoid oscillation.
+const MAX_STEP_TICKS: i8 = 2;
+
+/// Minimum time between two pacing corrections sent to the same client.
+const EMIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Tracks a single connection's input-buffer occupancy and decides when/how much to tell the
+/// client to adjust its input send-ahead.
+pub(crate) struct InputPacingController {
+    target_depth_ticks: f32,
+    ema_depth_ticks: f32,
+    ema_fallback_rate: f32,
+    time_since_last_emit: Duration,
+    /// Correction computed but not yet sent; drained by `Connection::send_packets`.
+    pending_correction: Option<i8>,
+}
+
+impl InputPacingController {
+    pub(crate) fn new(target_depth_ticks: f32) -> Self {
+        Self {
+            target_depth_ticks,
+            ema_depth_ticks: target_depth_ticks,
+            ema_fallback_rate: 0.0,
+            time_since_last_emit: Duration::ZERO,
+            pending_correction: None,
+        }
+    }
+
+    /// Feeds in one tick's observation: how many ticks of buffered input we actually had
+    /// ahead of the current tick (0 if we had to fall back), and whether this tick fell back.
+    pub(crate) fn record_sample(&mut self, observed_depth_ticks: f32, was_fallback: bool) {
+        self.ema_depth_ticks =
+            EMA_ALPHA * observed_depth_ticks + (1.0 - EMA_ALPHA) * self.ema_depth_ticks;
+        let fallback_sample = if was_fallback { 1.0 } else { 0.0 };
+        self.ema_fallback_rate =
+            EMA_ALPHA * fallback_sample + (1.0 - EMA_ALPHA) * self.ema_fallback_rate;
+    }
+
+    /// Call once per `update` with the elapsed time; while still syncing, feedback is
+    /// suppressed entirely (the client's timing isn't trustworthy yet).
+    pub(crate) fn update(&mut self, delta: Duration, is_syncing: bool) {
+        if is_syncing {
+            self.time_since_last_emit = Duration::ZERO;
+            self.pending_correction = None;
+            return;
+        }
+        self.time_since_last_emit += delta;
+        if self.time_since_last_emit < EMIT_INTERVAL {
+            return;
+        }
+        self.time_since_last_emit = Duration::ZERO;
+
+        let raw_correction = self.target_depth_ticks - self.ema_depth_ticks;
+        let clamped = raw_correction.clamp(-(MAX_STEP_TICKS as f32), MAX_STEP_TICKS as f32);
+        let delta_ticks = clamped.round() as i8;
+        if delta_ticks != 0 {
+            self.pending_correction = Some(delta_ticks);
+        }
+    }
+
+    /// Takes the pending correction, if any, clearing it.
+    pub(crate) fn take_pending_correction(&mut self) -> Option<i8> {
+        self.pending_correction.take()
+    }
+}
@@ -0,0 +1,152 @@
+//! Per-channel QoS: priority weights and a per-tick bandwidth budget for `send_packets`.
+//!
+//! [`PriorityScheduler`] implements a priority-accumulator scheduler: every send cycle each
+//! channel's accumulator grows by its weight, channels are drained highest-accumulator-first
+//! until the tick's byte budget runs out, and a channel that actually sends has its
+//! accumulator reduced by the bytes it sent. That lets a starved, low-weight channel's
+//! accumulator climb over several ticks until it finally outranks busier channels, instead of
+//! being permanently crowded out by FIFO draining.
+//!
+//! `PingChannel` and sync traffic are marked "always-send": they're scheduled ahead of every
+//! other channel regardless of accumulator, so latency measurement is never delayed behind
+//! bulk replication traffic.
+//!
+//! `Connection::send_packets` drains channels through
+//! `MessageManager::send_packets_prioritized`, which walks `schedule_order` and reports back
+//! the bytes each channel actually sent via [`PriorityScheduler::record_sent`].
+use bevy::utils::{HashMap, HashSet};
+
+use crate::prelude::ChannelKind;
+
+/// Default per-channel priority weight when none has been set explicitly.
+const DEFAULT_WEIGHT: u32 = 1;
+
+/// Tracks per-channel send priority and schedules channels for a tick's byte budget.
+#[derive(Default)]
+pub(crate) struct PriorityScheduler {
+    weights: HashMap<ChannelKind, u32>,
+    accumulators: HashMap<ChannelKind, i64>,
+    /// Channels that bypass the accumulator ordering entirely and are always scheduled first
+    /// (ping/pong, time sync).
+    always_send: HashSet<ChannelKind>,
+}
+
+impl PriorityScheduler {
+    pub(crate) fn set_weight(&mut self, channel: ChannelKind, weight: u32) {
+        self.weights.insert(channel, weight);
+        self.accumulators.entry(channel).or_insert(0);
+    }
+
+    pub(crate) fn mark_always_send(&mut self, channel: ChannelKind) {
+        self.always_send.insert(channel);
+        self.accumulators.entry(channel).or_insert(0);
+    }
+
+    /// Bumps every known channel's accumulator by its weight. Call once at the start of each
+    /// `send_packets` cycle, before scheduling.
+    ///
+    /// `registered_channels` is the full set of channels this connection's `MessageManager`
+    /// actually has open; any channel in there that hasn't gone through `set_weight`/
+    /// `mark_always_send` is registered here at [`DEFAULT_WEIGHT`], so a channel nobody bothered
+    /// to configure still gets scheduled instead of silently falling out of every cycle.
+    pub(crate) fn begin_cycle(&mut self, registered_channels: impl IntoIterator<Item = ChannelKind>) {
+        for channel in registered_channels {
+            self.accumulators.entry(channel).or_insert(0);
+        }
+        for (channel, acc) in self.accumulators.iter_mut() {
+            let weight = self.weights.get(channel).copied().unwrap_or(DEFAULT_WEIGHT) as i64;
+            *acc += weight;
+        }
+    }
+
+    /// Returns the channels to drain this cycle, always-send channels first, then the rest in
+    /// descending accumulator order.
+    pub(crate) fn schedule_order(&self) -> Vec<ChannelKind> {
+        let mut channels: Vec<ChannelKind> = self.accumulators.keys().copied().collect();
+        channels.sort_by(|a, b| {
+            let a_always = self.always_send.contains(a);
+            let b_always = self.always_send.contains(b);
+            b_always
+                .cmp(&a_always)
+                .then_with(|| self.accumulators[b].cmp(&self.accumulators[a]))
+        });
+        channels
+    }
+
+    /// Reduces `channel`'s accumulator by the number of bytes it actually sent this cycle.
+    pub(crate) fn record_sent(&mut self, channel: ChannelKind, bytes_sent: usize) {
+        if let Some(acc) = self.accumulators.get_mut(&channel) {
+            *acc -= bytes_sent as i64;
+        }
+    }
+}
+
+/// A per-tick byte budget derived from a configurable bandwidth cap (bytes/second) and the
+/// server's send rate. Does not carry debt (positive or negative) across ticks: every tick
+/// starts back at `cap_per_tick`, it's just a ceiling on that tick's sends.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BandwidthBudget {
+    pub(crate) cap_per_tick: usize,
+}
+
+impl BandwidthBudget {
+    pub(crate) fn from_bytes_per_second(bytes_per_second: usize, send_hz: f64) -> Self {
+        let cap_per_tick = ((bytes_per_second as f64) / send_hz.max(1.0)) as usize;
+        Self { cap_per_tick }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Busy;
+    struct Starved;
+    struct NeverConfigured;
+    struct Ping;
+
+    #[test]
+    fn begin_cycle_schedules_channels_nobody_explicitly_configured() {
+        let mut scheduler = PriorityScheduler::default();
+        scheduler.set_weight(ChannelKind::of::<Busy>(), 5);
+        scheduler.begin_cycle([ChannelKind::of::<Busy>(), ChannelKind::of::<NeverConfigured>()]);
+        let order = scheduler.schedule_order();
+        assert!(order.contains(&ChannelKind::of::<NeverConfigured>()));
+    }
+
+    #[test]
+    fn always_send_channels_are_scheduled_first_regardless_of_accumulator() {
+        let mut scheduler = PriorityScheduler::default();
+        scheduler.set_weight(ChannelKind::of::<Busy>(), 100);
+        scheduler.mark_always_send(ChannelKind::of::<Ping>());
+        scheduler.begin_cycle([]);
+        let order = scheduler.schedule_order();
+        assert_eq!(order[0], ChannelKind::of::<Ping>());
+    }
+
+    #[test]
+    fn a_starved_low_weight_channel_eventually_outranks_a_busy_one() {
+        let mut scheduler = PriorityScheduler::default();
+        let busy = ChannelKind::of::<Busy>();
+        let starved = ChannelKind::of::<Starved>();
+        scheduler.set_weight(busy, 10);
+        scheduler.set_weight(starved, 1);
+
+        let mut starved_went_first = false;
+        for _ in 0..20 {
+            scheduler.begin_cycle([]);
+            let order = scheduler.schedule_order();
+            if order[0] == starved {
+                starved_went_first = true;
+                break;
+            }
+            // `busy` is drained every cycle and pays back its accumulator; `starved` is never
+            // sent, so its accumulator only ever grows
+            scheduler.record_sent(busy, 10_000);
+        }
+        assert!(
+            starved_went_first,
+            "a channel starved for 20 cycles should eventually outrank a channel that sends every cycle"
+        );
+    }
+}
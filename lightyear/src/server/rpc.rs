@@ -0,0 +1,185 @@
+//! Typed request/response RPC layered on top of the fire-and-forget message channels.
+//!
+//! [`RpcState`] tracks outgoing requests awaiting a reply (`request_id -> oneshot sender`)
+//! and incoming handlers registered per request type, so gameplay code can `request(...)` the
+//! other side and `.await` the answer instead of correlating replies through
+//! `ConnectionEvents` by hand.
+//!
+//! Requests carry a `request_kind` (`std::any::type_name::<Req>()`) alongside the raw
+//! payload, because the receiving side only ever sees `request_id` + bytes off the wire and
+//! has no `Req` type of its own to recover a `TypeId` from; the sender's type name travels
+//! with the request so the receiver can look up the matching handler by name instead.
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use bevy::utils::HashMap;
+use futures::channel::oneshot;
+use tracing::{debug, trace};
+
+/// Per-connection id correlating an outgoing request with its eventual response.
+pub(crate) type RequestId = u32;
+
+/// A five-second request timeout. `Connection::request` takes its timeout as an argument and
+/// does not fall back to this value on its own.
+pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct PendingRequest {
+    sender: oneshot::Sender<Result<Vec<u8>>>,
+    expires_at: Instant,
+}
+
+/// Type-erased handler for an incoming request of a specific `Req` type; stored boxed so
+/// handlers for different request types can live in the same table.
+type BoxedHandler = Box<dyn Fn(&[u8]) -> Result<Vec<u8>> + Send + Sync>;
+
+/// Per-connection RPC bookkeeping: outstanding requests we're waiting on a reply for, and
+/// handlers registered to answer requests the other side sends us.
+#[derive(Default)]
+pub(crate) struct RpcState {
+    next_request_id: AtomicU32,
+    pending: HashMap<RequestId, PendingRequest>,
+    handlers: HashMap<&'static str, BoxedHandler>,
+}
+
+impl RpcState {
+    /// Allocates a fresh request id and registers `sender` to be completed once the matching
+    /// response arrives (or the request times out).
+    pub(crate) fn start_request(
+        &mut self,
+        timeout: Duration,
+    ) -> (RequestId, oneshot::Receiver<Result<Vec<u8>>>) {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.pending.insert(
+            request_id,
+            PendingRequest {
+                sender,
+                expires_at: Instant::now() + timeout,
+            },
+        );
+        trace!(request_id, "registered pending RPC request");
+        (request_id, receiver)
+    }
+
+    /// Completes the pending request `request_id` with the raw response bytes. No-op (besides
+    /// a debug log) if the request already timed out or doesn't exist, e.g. a late duplicate
+    /// reply on an unreliable channel.
+    pub(crate) fn complete_request(&mut self, request_id: RequestId, response: Vec<u8>) {
+        match self.pending.remove(&request_id) {
+            Some(pending) => {
+                let _ = pending.sender.send(Ok(response));
+            }
+            None => debug!(request_id, "received response for unknown/expired RPC request"),
+        }
+    }
+
+    /// Fails the pending request `request_id` with `message`, e.g. because the other side
+    /// reported it has no handler for it. Lets the caller's future resolve immediately instead
+    /// of waiting out the full timeout for a reply that will never come. No-op (besides a
+    /// debug log) if the request already timed out or doesn't exist.
+    pub(crate) fn fail_request(&mut self, request_id: RequestId, message: String) {
+        match self.pending.remove(&request_id) {
+            Some(pending) => {
+                let _ = pending.sender.send(Err(anyhow!("{message}")));
+            }
+            None => debug!(request_id, "received error for unknown/expired RPC request"),
+        }
+    }
+
+    /// Fails and evicts any pending requests whose deadline has passed.
+    pub(crate) fn expire_timed_out(&mut self, now: Instant) {
+        let expired: Vec<RequestId> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.expires_at <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for request_id in expired {
+            if let Some(pending) = self.pending.remove(&request_id) {
+                debug!(request_id, "RPC request timed out");
+                let _ = pending.sender.send(Err(anyhow!("RPC request {request_id} timed out")));
+            }
+        }
+    }
+
+    /// Registers the handler invoked to answer incoming requests of type `Req`.
+    pub(crate) fn register_handler<Req: 'static>(
+        &mut self,
+        handler: impl Fn(&[u8]) -> Result<Vec<u8>> + Send + Sync + 'static,
+    ) {
+        self.handlers
+            .insert(std::any::type_name::<Req>(), Box::new(handler));
+    }
+
+    /// Looks up the handler registered for `request_kind` (the sender's
+    /// `type_name::<Req>()`) and runs it against the raw request bytes.
+    pub(crate) fn dispatch_request(&self, request_kind: &str, request_bytes: &[u8]) -> Result<Vec<u8>> {
+        self.handlers
+            .get(request_kind)
+            .ok_or_else(|| anyhow!("no RPC handler registered for request kind {request_kind:?}"))
+            .and_then(|handler| handler(request_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ping;
+
+    #[test]
+    fn complete_request_resolves_the_matching_receiver() {
+        let mut rpc = RpcState::default();
+        let (request_id, mut receiver) = rpc.start_request(DEFAULT_REQUEST_TIMEOUT);
+        rpc.complete_request(request_id, b"pong".to_vec());
+        assert_eq!(receiver.try_recv().unwrap().unwrap().unwrap(), b"pong".to_vec());
+    }
+
+    #[test]
+    fn complete_request_is_a_noop_for_an_unknown_request_id() {
+        let mut rpc = RpcState::default();
+        // no pending request was ever started with this id; must not panic
+        rpc.complete_request(42, b"pong".to_vec());
+    }
+
+    #[test]
+    fn expire_timed_out_fails_only_requests_past_their_deadline() {
+        let mut rpc = RpcState::default();
+        let now = Instant::now();
+        let (expired_id, mut expired_receiver) = rpc.start_request(Duration::from_secs(1));
+        let (live_id, mut live_receiver) = rpc.start_request(Duration::from_secs(100));
+
+        rpc.expire_timed_out(now + Duration::from_secs(2));
+
+        assert!(expired_receiver.try_recv().unwrap().unwrap().is_err());
+        assert!(live_receiver.try_recv().unwrap().is_none());
+        // the expired request was evicted, so completing it later is a no-op
+        rpc.complete_request(expired_id, b"late".to_vec());
+        assert!(rpc.pending.contains_key(&live_id));
+    }
+
+    #[test]
+    fn fail_request_resolves_the_matching_receiver_with_an_error() {
+        let mut rpc = RpcState::default();
+        let (request_id, mut receiver) = rpc.start_request(DEFAULT_REQUEST_TIMEOUT);
+        rpc.fail_request(request_id, "no handler".to_string());
+        assert!(receiver.try_recv().unwrap().unwrap().is_err());
+    }
+
+    #[test]
+    fn dispatch_request_routes_to_the_handler_registered_for_the_matching_type() {
+        let mut rpc = RpcState::default();
+        rpc.register_handler::<Ping>(|bytes| Ok(bytes.to_vec()));
+        let response = rpc
+            .dispatch_request(std::any::type_name::<Ping>(), b"hello")
+            .unwrap();
+        assert_eq!(response, b"hello".to_vec());
+    }
+
+    #[test]
+    fn dispatch_request_errors_for_an_unregistered_request_kind() {
+        let rpc = RpcState::default();
+        assert!(rpc.dispatch_request("not::a::registered::kind", b"hello").is_err());
+    }
+}
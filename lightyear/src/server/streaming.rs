@@ -0,0 +1,289 @@
+//! Fragmented transfer of large messages that don't fit in a single packet.
+//!
+//! A [`StreamSender`] splits an outgoing payload into fixed-size fragments and hands them
+//! out to `send_packets` a few at a time, subject to a per-tick byte budget. A
+//! [`StreamReceiver`] reassembles fragments coming from a single client back into the
+//! original payload, tolerating out-of-order arrival on reliable channels.
+//!
+use bevy::utils::HashMap;
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Result};
+use tracing::{debug, trace};
+
+use crate::prelude::ChannelKind;
+
+/// Per-connection, monotonically increasing id identifying a single streamed message.
+pub(crate) type StreamId = u32;
+
+/// Maximum payload size (in bytes) carried by a single fragment.
+///
+/// Chosen comfortably under the ~16k MTU budget so that a fragment plus its framing still
+/// fits in one packet alongside other traffic.
+pub(crate) const FRAGMENT_SIZE: usize = 1200;
+
+/// Hard cap on the number of streams a single connection may have in flight at once, to
+/// bound memory usage under a memory-exhaustion attack.
+pub(crate) const MAX_IN_FLIGHT_STREAMS: usize = 8;
+
+/// Hard cap on the total number of buffered fragment bytes (sent-but-not-yet-flushed or
+/// received-but-not-yet-complete) per connection.
+pub(crate) const MAX_BUFFERED_BYTES: usize = 8 * 1024 * 1024;
+
+/// A single fragment of a streamed message.
+#[derive(Debug, Clone)]
+pub(crate) struct StreamFragment {
+    pub(crate) stream_id: StreamId,
+    pub(crate) fragment_index: u32,
+    /// Total number of fragments that make up this stream; carried on every fragment so the
+    /// receiver doesn't need to wait for a dedicated "header" fragment.
+    pub(crate) num_fragments: u32,
+    pub(crate) is_last: bool,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// Splits outgoing payloads into fragments and doles them out a budget's worth at a time.
+#[derive(Default)]
+pub(crate) struct StreamSender {
+    next_stream_id: StreamId,
+    /// Fragments queued for sending, oldest-first. `(channel, fragment)`.
+    pending: std::collections::VecDeque<(ChannelKind, StreamFragment)>,
+    /// Bytes currently sitting in `pending`, tracked incrementally so enforcing
+    /// `MAX_BUFFERED_BYTES` doesn't require re-summing the queue.
+    buffered_bytes: usize,
+    in_flight_streams: usize,
+}
+
+impl StreamSender {
+    /// Splits `payload` into fragments and queues them for sending on `channel`.
+    ///
+    /// Returns the id assigned to this stream so the caller can correlate completion/acks
+    /// later on if needed.
+    pub(crate) fn start_stream(
+        &mut self,
+        payload: Vec<u8>,
+        channel: ChannelKind,
+    ) -> Result<StreamId> {
+        ensure!(
+            self.in_flight_streams < MAX_IN_FLIGHT_STREAMS,
+            "too many in-flight streams on this connection"
+        );
+        ensure!(
+            self.buffered_bytes + payload.len() <= MAX_BUFFERED_BYTES,
+            "stream would exceed the per-connection buffered byte budget"
+        );
+
+        let stream_id = self.next_stream_id;
+        self.next_stream_id = self.next_stream_id.wrapping_add(1);
+
+        // `[].chunks(N)` yields zero chunks, which would leave `in_flight_streams`
+        // incremented below with no `is_last` fragment ever queued to decrement it back
+        // (a permanent leak of an in-flight slot for an empty payload). Emit a single
+        // zero-length, `is_last` fragment instead so the stream still completes normally.
+        let chunks: Vec<&[u8]> = if payload.is_empty() {
+            vec![&payload[..]]
+        } else {
+            payload.chunks(FRAGMENT_SIZE).collect()
+        };
+        let num_fragments = chunks.len() as u32;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let fragment = StreamFragment {
+                stream_id,
+                fragment_index: i as u32,
+                num_fragments,
+                is_last: i as u32 == num_fragments - 1,
+                payload: chunk.to_vec(),
+            };
+            self.buffered_bytes += fragment.payload.len();
+            self.pending.push_back((channel, fragment));
+        }
+        self.in_flight_streams += 1;
+        debug!(?stream_id, num_fragments, "started outgoing stream");
+        Ok(stream_id)
+    }
+
+    /// Pops fragments to send this tick until `byte_budget` is exhausted or no fragments
+    /// remain. Leftover fragments stay queued for the next call.
+    pub(crate) fn drain(&mut self, mut byte_budget: usize) -> Vec<(ChannelKind, StreamFragment)> {
+        let mut drained = vec![];
+        while let Some((channel, fragment)) = self.pending.front() {
+            if fragment.payload.len() > byte_budget {
+                break;
+            }
+            byte_budget -= fragment.payload.len();
+            let (channel, fragment) = self.pending.pop_front().unwrap();
+            self.buffered_bytes -= fragment.payload.len();
+            if fragment.is_last {
+                self.in_flight_streams -= 1;
+            }
+            drained.push((channel, fragment));
+        }
+        drained
+    }
+
+    /// Drops all queued-but-unsent fragments, e.g. when the owning connection is removed.
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+        self.buffered_bytes = 0;
+        self.in_flight_streams = 0;
+    }
+}
+
+/// Partially reassembled state for a single incoming stream.
+struct PendingStream {
+    num_fragments: u32,
+    bytes_received: usize,
+    fragments: BTreeMap<u32, Vec<u8>>,
+    /// Set once a fragment claiming `is_last` has arrived; completion requires this in
+    /// addition to having `num_fragments` distinct fragments, so a stream can't be considered
+    /// done purely by count.
+    saw_last: bool,
+}
+
+/// Reassembles fragments from a single connection back into complete messages.
+#[derive(Default)]
+pub(crate) struct StreamReceiver {
+    streams: HashMap<StreamId, PendingStream>,
+    total_buffered_bytes: usize,
+}
+
+impl StreamReceiver {
+    /// Feeds a fragment in. Returns `Some(payload)` once `is_last` has been seen and there
+    /// are no gaps left in the fragment sequence.
+    pub(crate) fn receive_fragment(&mut self, fragment: StreamFragment) -> Result<Option<Vec<u8>>> {
+        ensure!(fragment.num_fragments > 0, "stream fragment claims zero total fragments");
+        ensure!(
+            fragment.fragment_index < fragment.num_fragments,
+            "fragment index {} is out of range for a stream of {} fragments",
+            fragment.fragment_index,
+            fragment.num_fragments
+        );
+        if !self.streams.contains_key(&fragment.stream_id) {
+            ensure!(
+                self.streams.len() < MAX_IN_FLIGHT_STREAMS,
+                "too many in-flight incoming streams; dropping fragment"
+            );
+        }
+        ensure!(
+            self.total_buffered_bytes + fragment.payload.len() <= MAX_BUFFERED_BYTES,
+            "incoming stream would exceed the per-connection buffered byte budget"
+        );
+
+        let num_fragments = fragment.num_fragments;
+        let fragment_len = fragment.payload.len();
+        let fragment_is_last = fragment.is_last;
+        let entry = self.streams.entry(fragment.stream_id).or_insert_with(|| PendingStream {
+            num_fragments,
+            bytes_received: 0,
+            fragments: BTreeMap::new(),
+            saw_last: false,
+        });
+        ensure!(
+            entry.num_fragments == num_fragments,
+            "stream {} fragment claims {} total fragments, but earlier fragments claimed {}",
+            fragment.stream_id,
+            num_fragments,
+            entry.num_fragments
+        );
+        if entry.fragments.insert(fragment.fragment_index, fragment.payload).is_none() {
+            entry.bytes_received += fragment_len;
+            self.total_buffered_bytes += fragment_len;
+        }
+        entry.saw_last |= fragment_is_last;
+
+        // only complete once we've both seen the `is_last` marker and hold exactly
+        // `num_fragments` distinct, in-range indices (so indices 0..num_fragments are all
+        // present with no gaps, by the pigeonhole principle)
+        if entry.saw_last && entry.fragments.len() as u32 == entry.num_fragments {
+            let entry = self.streams.remove(&fragment.stream_id).unwrap();
+            self.total_buffered_bytes -= entry.bytes_received;
+            let mut full = Vec::with_capacity(entry.bytes_received);
+            for (_, chunk) in entry.fragments {
+                full.extend_from_slice(&chunk);
+            }
+            trace!(stream_id = fragment.stream_id, "reassembled stream complete");
+            return Ok(Some(full));
+        }
+        Ok(None)
+    }
+
+    /// Drops all in-progress streams, e.g. when the owning connection is removed.
+    pub(crate) fn clear(&mut self) {
+        self.streams.clear();
+        self.total_buffered_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment(stream_id: StreamId, fragment_index: u32, num_fragments: u32, payload: &[u8]) -> StreamFragment {
+        StreamFragment {
+            stream_id,
+            fragment_index,
+            num_fragments,
+            is_last: fragment_index == num_fragments - 1,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn empty_payload_does_not_leak_an_in_flight_slot() {
+        let mut sender = StreamSender::default();
+        for _ in 0..MAX_IN_FLIGHT_STREAMS {
+            sender.start_stream(vec![], ChannelKind::of::<()>()).unwrap();
+        }
+        // every empty stream should drain its single zero-length `is_last` fragment
+        // immediately, freeing its slot back up
+        let drained = sender.drain(usize::MAX);
+        assert_eq!(drained.len(), MAX_IN_FLIGHT_STREAMS);
+        assert!(sender.start_stream(vec![], ChannelKind::of::<()>()).is_ok());
+    }
+
+    #[test]
+    fn reassembles_out_of_order_fragments() {
+        let mut receiver = StreamReceiver::default();
+        assert!(receiver.receive_fragment(fragment(1, 2, 3, b"ghi")).unwrap().is_none());
+        assert!(receiver.receive_fragment(fragment(1, 0, 3, b"abc")).unwrap().is_none());
+        let full = receiver.receive_fragment(fragment(1, 1, 3, b"def")).unwrap();
+        assert_eq!(full, Some(b"abcdefghi".to_vec()));
+    }
+
+    #[test]
+    fn does_not_complete_with_a_gap_even_if_is_last_seen() {
+        let mut receiver = StreamReceiver::default();
+        // indices 0 and 2 (is_last) arrive, but index 1 never does: must not complete
+        assert!(receiver.receive_fragment(fragment(1, 0, 3, b"abc")).unwrap().is_none());
+        assert!(receiver.receive_fragment(fragment(1, 2, 3, b"ghi")).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_fragment_index_out_of_range() {
+        let mut receiver = StreamReceiver::default();
+        assert!(receiver.receive_fragment(fragment(1, 3, 3, b"xyz")).is_err());
+    }
+
+    #[test]
+    fn rejects_inconsistent_num_fragments() {
+        let mut receiver = StreamReceiver::default();
+        assert!(receiver.receive_fragment(fragment(1, 0, 3, b"abc")).unwrap().is_none());
+        assert!(receiver.receive_fragment(fragment(1, 1, 4, b"def")).is_err());
+    }
+
+    #[test]
+    fn rejects_payload_over_the_buffered_byte_budget() {
+        let mut sender = StreamSender::default();
+        let oversized = vec![0u8; MAX_BUFFERED_BYTES + 1];
+        assert!(sender.start_stream(oversized, ChannelKind::of::<()>()).is_err());
+    }
+
+    #[test]
+    fn rejects_too_many_in_flight_streams() {
+        let mut sender = StreamSender::default();
+        for _ in 0..MAX_IN_FLIGHT_STREAMS {
+            sender.start_stream(vec![1, 2, 3], ChannelKind::of::<()>()).unwrap();
+        }
+        assert!(sender.start_stream(vec![1, 2, 3], ChannelKind::of::<()>()).is_err());
+    }
+}